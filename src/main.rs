@@ -1,14 +1,15 @@
 extern crate clap;
 extern crate fidget;
 
-use clap::Parser;
-use fidget::context::{Context, Tree};
+use clap::{Parser, ValueEnum};
+use fidget::context::{Context, Node, Tree};
 use fidget::jit::JitShape;
 use fidget::mesh::{Octree, Settings};
 use fidget::shape::Bounds;
 use fidget::vm::VmData;
 use indexmap::IndexMap;
 use nalgebra::base::{Vector2, Vector3};
+use std::collections::HashMap;
 use std::f64::consts::PI;
 use std::fs;
 use std::io::Write;
@@ -30,8 +31,34 @@ fn bounded_box(x_min: f64, y_min: f64, z_min: f64, x_max: f64, y_max: f64, z_max
     )
 }
 
+/// Pattern used to fill the interior of a layer.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum InfillType {
+    /// Triply-periodic minimal surface (gyroid) infill, intersected with the object SDF directly.
+    Gyroid,
+    /// Parallel straight lines, alternating angle every other layer.
+    Rectilinear,
+}
+
+/// Where to place the seam (start/stop point) of each perimeter loop.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum SeamPosition {
+    /// Snap to the vertex nearest a fixed reference point, preferring concave corners,
+    /// so seams stack vertically across layers instead of scattering.
+    Aligned,
+    /// The vertex with the highest Y, so the seam lands on the back of the part.
+    Rear,
+    /// The vertex closest to wherever the nozzle already is, to cut travel.
+    Nearest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum LayerType {
     Standard,
+    /// Sparse sacrificial material printed under an overhang so it has something to
+    /// rest on; never produced as a `Layer` itself, only used to tag the
+    /// `ExtrusionPath`s generated for it within a `Standard` layer.
+    Support,
 }
 
 struct Layer {
@@ -50,11 +77,24 @@ impl Layer {
 
 #[derive(Debug, Clone)]
 struct ExtrusionPath {
-    path_width: f64,
+    // One width per point in `paths` (same length), rather than a single scalar, so
+    // variable-width perimeters can narrow or widen along their length.
+    path_width: Vec<f64>,
     path_height: f64,
     z_height: f64,
     extruder_cross_sectional_area_per_mm: f64,
     paths: Vec<Vector2<f32>>,
+    // Perimeter loops are used as obstacles for avoid-crossing travel routing; infill
+    // and other fill paths are not.
+    is_perimeter: bool,
+    // Which kind of layer this path belongs to (currently only used to distinguish
+    // support material from the object itself).
+    layer_type: LayerType,
+    // `Some(diameter)` when this path spans a gap with nothing underneath: flow is
+    // computed as a round filament cross-section of this diameter instead of the
+    // usual squished rectangle-plus-semicircles profile, since there's no layer below
+    // for the bead to squash against. `None` for ordinary, supported extrusion.
+    bridge_diameter: Option<f64>,
 }
 
 impl ExtrusionPath {
@@ -64,20 +104,27 @@ impl ExtrusionPath {
         z_height: f64,
         extruder_cross_sectional_area_per_mm: f64,
         start: Vector2<f32>,
+        is_perimeter: bool,
+        layer_type: LayerType,
+        bridge_diameter: Option<f64>,
     ) -> ExtrusionPath {
         let mut paths = Vec::new();
         paths.push(start);
         ExtrusionPath {
-            path_width: path_width,
+            path_width: vec![path_width],
             path_height: path_height,
             z_height: z_height,
             extruder_cross_sectional_area_per_mm: extruder_cross_sectional_area_per_mm,
             paths: paths,
+            is_perimeter: is_perimeter,
+            layer_type: layer_type,
+            bridge_diameter: bridge_diameter,
         }
     }
 
-    fn add_to_path(&mut self, point: Vector2<f32>) {
+    fn add_to_path(&mut self, point: Vector2<f32>, width: f64) {
         self.paths.push(point);
+        self.path_width.push(width);
     }
 
     fn first_point_in_path(&self) -> Option<&Vector2<f32>> {
@@ -104,42 +151,491 @@ impl ExtrusionPath {
         }
     }
 
-    fn write_gcode<F: std::io::Write>(&self, out: &mut F) -> Result<(), std::io::Error> {
-        let extrusion_cross_section_area = (self.path_width - self.path_height) * self.path_height
-            + PI * (self.path_height / 2.).powi(2);
-        match self.paths.first() {
-            Some(first_point) => {
-                write!(out, "G1 Z{:.6}\n", self.z_height)?;
-                write!(
-                    out,
-                    "G1 X{:.6} Y{:.6} Z{:.6}\n",
-                    first_point.x, first_point.y, self.z_height
-                )?;
-            }
-            None => return Ok(()),
+    // Total length of this path's travel, in mm, used to estimate layer print time.
+    fn length(&self) -> f64 {
+        self.paths
+            .windows(2)
+            .map(|s| (s[1] - s[0]).norm() as f64)
+            .sum()
+    }
+
+    // Writes this path's motion, assuming the nozzle is already sitting at `paths[0]`
+    // (getting it there - travel, retraction, Z-hop - is the caller's job, see
+    // `emit_gcode`). `extruder_position` is the machine's absolute E and is threaded
+    // through so distances accumulate correctly across paths instead of resetting.
+    // `feedrate` is this path's (possibly cooling-scaled) speed; it's only stamped on
+    // the first move since the printer holds whatever F it's last told.
+    fn write_gcode<F: std::io::Write>(
+        &self,
+        out: &mut F,
+        extruder_position: &mut f64,
+        feedrate: f64,
+    ) -> Result<(), std::io::Error> {
+        if self.paths.is_empty() {
+            return Ok(());
         }
-        for (point_0, point_1) in self.paths.windows(2).map(|s| (s[0], s[1])) {
+        write!(out, "G1 Z{:.6}\n", self.z_height)?;
+        let segment_points = self.paths.windows(2).map(|s| (s[0], s[1]));
+        let segment_widths = self.path_width.windows(2).map(|s| (s[0], s[1]));
+        let mut first_segment = true;
+        for ((point_0, point_1), (width_0, width_1)) in segment_points.zip(segment_widths) {
+            // Cross-section for this segment from the width at each of its endpoints
+            // averaged together, so tapering widths blend smoothly along the path.
+            let segment_width = (width_0 + width_1) / 2.;
+            let extrusion_cross_section_area = match self.bridge_diameter {
+                Some(diameter) => PI * (diameter / 2.).powi(2),
+                None => {
+                    (segment_width - self.path_height) * self.path_height
+                        + PI * (self.path_height / 2.).powi(2)
+                }
+            };
             let extrusion_volume =
                 ((point_1 - point_0).norm() as f64) * extrusion_cross_section_area;
             let extruder_distance = extrusion_volume / self.extruder_cross_sectional_area_per_mm;
-            write!(
-                out,
-                "G1 X{:.6} Y{:.6} E{:.6}\n",
-                point_1.x, point_1.y, extruder_distance
-            )?;
+            *extruder_position += extruder_distance;
+            if first_segment {
+                write!(
+                    out,
+                    "G1 X{:.6} Y{:.6} E{:.6} F{:.0}\n",
+                    point_1.x, point_1.y, *extruder_position, feedrate
+                )?;
+                first_segment = false;
+            } else {
+                write!(
+                    out,
+                    "G1 X{:.6} Y{:.6} E{:.6}\n",
+                    point_1.x, point_1.y, *extruder_position
+                )?;
+            }
         }
         Ok(())
     }
 }
 
+// Short backward move along the tail of `path`, used to wipe while retracting so the
+// nozzle doesn't leave a blob at the end of a perimeter or infill line.
+fn wipe_point(path: &ExtrusionPath, wipe_distance: f64) -> Option<Vector2<f32>> {
+    let n = path.paths.len();
+    if n < 2 || wipe_distance <= 0. {
+        return None;
+    }
+    let last = path.paths[n - 1];
+    let direction = last - path.paths[n - 2];
+    let length = direction.norm();
+    if length < 1e-6 {
+        return None;
+    }
+    Some(last - direction * ((wipe_distance as f32) / length))
+}
+
+// Rotates a closed loop's point sequence (and its parallel per-point widths) so it
+// begins at `seam_index`, re-closing the loop by re-appending the new start point.
+// `path.paths` is assumed closed (first point duplicates last), as every loop
+// `walk_layer_tree` produces is.
+fn rotate_closed_loop(path: &mut ExtrusionPath, seam_index: usize) {
+    let n = path.paths.len();
+    if seam_index == 0 || seam_index >= n - 1 {
+        return;
+    }
+    let mut rotated_paths = path.paths[seam_index..n - 1].to_vec();
+    rotated_paths.extend_from_slice(&path.paths[..seam_index]);
+    rotated_paths.push(rotated_paths[0]);
+    let mut rotated_widths = path.path_width[seam_index..n - 1].to_vec();
+    rotated_widths.extend_from_slice(&path.path_width[..seam_index]);
+    rotated_widths.push(rotated_widths[0]);
+    path.paths = rotated_paths;
+    path.path_width = rotated_widths;
+}
+
+// The exterior turn angle at `curr`, in radians: positive for a convex corner,
+// negative for a concave one (for a consistently-wound loop), zero for a straight run.
+fn exterior_turn_angle(prev: Vector2<f32>, curr: Vector2<f32>, next: Vector2<f32>) -> f64 {
+    let incoming = curr - prev;
+    let outgoing = next - curr;
+    let cross = (incoming.x * outgoing.y - incoming.y * outgoing.x) as f64;
+    let dot = (incoming.x * outgoing.x + incoming.y * outgoing.y) as f64;
+    cross.atan2(dot)
+}
+
+// How far a surface leans out past vertical, from its normal, in degrees: 0 for a vertical wall
+// (self-supporting), 90 for a flat downward-facing ceiling (maximum overhang).
+fn overhang_angle_degrees(normal: Vector3<f64>) -> f64 {
+    (-normal.z).clamp(-1., 1.).asin().to_degrees()
+}
+
+// True if segment (a0, a1) properly crosses segment (b0, b1) - i.e. they cross
+// transversally rather than merely touching at a shared endpoint. Touching is allowed
+// so travel can hug the inside of a perimeter.
+fn segments_properly_cross(
+    a0: Vector2<f32>,
+    a1: Vector2<f32>,
+    b0: Vector2<f32>,
+    b1: Vector2<f32>,
+) -> bool {
+    fn orient(p: Vector2<f32>, q: Vector2<f32>, r: Vector2<f32>) -> f32 {
+        (q.x - p.x) * (r.y - p.y) - (q.y - p.y) * (r.x - p.x)
+    }
+    let d1 = orient(b0, b1, a0);
+    let d2 = orient(b0, b1, a1);
+    let d3 = orient(a0, a1, b0);
+    let d4 = orient(a0, a1, b1);
+    ((d1 > 0. && d2 < 0.) || (d1 < 0. && d2 > 0.)) && ((d3 > 0. && d4 < 0.) || (d3 < 0. && d4 > 0.))
+}
+
+fn segment_crosses_any_perimeter(
+    a: Vector2<f32>,
+    b: Vector2<f32>,
+    perimeters: &[Vec<Vector2<f32>>],
+) -> bool {
+    perimeters.iter().any(|loop_pts| {
+        loop_pts
+            .iter()
+            .zip(loop_pts.iter().skip(1))
+            .any(|(p0, p1)| segments_properly_cross(a, b, *p0, *p1))
+    })
+}
+
+// Route a travel move from `start` to `end` that never properly crosses one of the
+// current layer's perimeter loops. Builds a visibility graph over `start`, `end`, and
+// every perimeter vertex (two nodes are connected when the segment between them
+// doesn't cross a perimeter edge), then runs Dijkstra for the shortest such path.
+// Falls back to the direct move if no perimeters are present, or no non-crossing path
+// exists.
+fn route_travel(
+    start: Vector2<f32>,
+    end: Vector2<f32>,
+    perimeters: &[Vec<Vector2<f32>>],
+) -> Vec<Vector2<f32>> {
+    if perimeters.is_empty() || !segment_crosses_any_perimeter(start, end, perimeters) {
+        return vec![start, end];
+    }
+
+    let mut nodes = vec![start, end];
+    for loop_pts in perimeters {
+        // A closed perimeter loop's last point duplicates its first; skip the
+        // duplicate. A bridge-split fragment is an open sub-polyline (no
+        // duplication), so every one of its points is a distinct waypoint
+        // candidate and none should be dropped.
+        let closed = loop_pts.len() > 1 && loop_pts.first() == loop_pts.last();
+        let take = if closed {
+            loop_pts.len() - 1
+        } else {
+            loop_pts.len()
+        };
+        nodes.extend(loop_pts.iter().take(take));
+    }
+
+    let n = nodes.len();
+    let mut adjacency = vec![Vec::<(usize, f32)>::new(); n];
+    for i in 0..n {
+        for j in (i + 1)..n {
+            if !segment_crosses_any_perimeter(nodes[i], nodes[j], perimeters) {
+                let weight = (nodes[j] - nodes[i]).norm();
+                adjacency[i].push((j, weight));
+                adjacency[j].push((i, weight));
+            }
+        }
+    }
+
+    const START: usize = 0;
+    const END: usize = 1;
+    let mut dist = vec![f32::INFINITY; n];
+    let mut prev = vec![usize::MAX; n];
+    let mut visited = vec![false; n];
+    dist[START] = 0.;
+    loop {
+        let u = (0..n)
+            .filter(|&v| !visited[v])
+            .min_by(|&a, &b| dist[a].partial_cmp(&dist[b]).unwrap());
+        let u = match u {
+            Some(u) if dist[u].is_finite() => u,
+            _ => break,
+        };
+        if u == END {
+            break;
+        }
+        visited[u] = true;
+        for &(v, w) in &adjacency[u] {
+            if dist[u] + w < dist[v] {
+                dist[v] = dist[u] + w;
+                prev[v] = u;
+            }
+        }
+    }
+
+    if !dist[END].is_finite() {
+        return vec![start, end];
+    }
+    let mut route = vec![END];
+    while *route.last().unwrap() != START {
+        route.push(prev[*route.last().unwrap()]);
+    }
+    route.reverse();
+    route.into_iter().map(|i| nodes[i]).collect()
+}
+
+// Cooling/speed settings shared by every layer's G-code emission.
+struct CoolingSettings {
+    perimeter_speed: f64,
+    infill_speed: f64,
+    first_layer_speed: f64,
+    min_layer_time: f64,
+    min_print_speed: f64,
+    fan_speed: f64,
+    bridge_speed: f64,
+}
+
+// The feedrate a path would nominally print at, before any per-layer cooling scale-down.
+// Bridges get their own fixed feedrate, unaffected by the first-layer or slowdown rules,
+// since they need to stay taut rather than match the surrounding material's pace.
+fn nominal_feedrate(path: &ExtrusionPath, is_first_layer: bool, cooling: &CoolingSettings) -> f64 {
+    if path.bridge_diameter.is_some() {
+        cooling.bridge_speed
+    } else if is_first_layer {
+        cooling.first_layer_speed
+    } else if path.is_perimeter {
+        cooling.perimeter_speed
+    } else {
+        cooling.infill_speed
+    }
+}
+
+// True for the innermost perimeter ring - index `total_perimeters - 1`. Ring 0 is the
+// outermost and each successive index is offset further inward by
+// `path_spacing * (perimeter + 0.5)` in the perimeter loop (which walks the indices in
+// reverse, innermost first, but that doesn't change which index is which), the
+// same convention `inner_region_tree`'s full-interior `+ path_spacing * perimeters`
+// offset relies on, so the innermost ring is the *last*, not the first, index.
+fn is_innermost_perimeter(perimeter: u64, total_perimeters: u64) -> bool {
+    perimeter == total_perimeters - 1
+}
+
+// Emits travel moves (with retraction, an optional Z-hop, a wipe, and optionally
+// perimeter-avoiding routing) whenever two consecutive paths don't already meet
+// end-to-end, instead of dragging the nozzle straight across the gap.
+//
+// `layers` is buffered per layer (rather than one flat list of paths) so the cooling
+// pass below can look at an entire layer's print time before committing to feedrates
+// for it.
+fn emit_gcode<F: std::io::Write>(
+    layers: &[Vec<ExtrusionPath>],
+    out: &mut F,
+    retraction_length: f64,
+    retraction_speed: f64,
+    z_hop: f64,
+    travel_speed: f64,
+    avoid_crossing_perimeters: bool,
+    cooling: &CoolingSettings,
+) -> Result<(), std::io::Error> {
+    let mut extruder_position = 0f64;
+    let mut previous: Option<&ExtrusionPath> = None;
+    let mut current_layer_perimeters: Vec<Vec<Vector2<f32>>> = Vec::new();
+    for (layer_index, paths) in layers.iter().enumerate() {
+        if paths.is_empty() {
+            continue;
+        }
+        let is_first_layer = layer_index == 0;
+
+        // Estimate this layer's print time at its nominal feedrates; if it's too fast
+        // to cool, scale every non-bridge feedrate on the layer down by the same factor
+        // (never below `min_print_speed`) to stretch the layer toward `min_layer_time`.
+        let estimated_seconds: f64 = paths
+            .iter()
+            .map(|path| {
+                path.length() as f64 / (nominal_feedrate(path, is_first_layer, cooling) / 60.)
+            })
+            .sum();
+        let slowdown = if estimated_seconds > 0. && estimated_seconds < cooling.min_layer_time {
+            estimated_seconds / cooling.min_layer_time
+        } else {
+            1.
+        };
+
+        let layer_fan_speed = if is_first_layer {
+            0.
+        } else {
+            cooling.fan_speed
+        };
+        write!(out, "M106 S{:.0}\n", layer_fan_speed)?;
+
+        current_layer_perimeters.clear();
+        for path in paths {
+            let first = match path.first_point_in_path() {
+                Some(p) => *p,
+                None => continue,
+            };
+            match previous {
+                Some(prev_path) => {
+                    let last = *prev_path
+                        .last_point_in_path()
+                        .expect("non-empty path, checked above");
+                    if last != first {
+                        let wipe_target = wipe_point(prev_path, retraction_length).unwrap_or(last);
+                        extruder_position -= retraction_length;
+                        write!(
+                            out,
+                            "G1 X{:.6} Y{:.6} E{:.6} F{:.0}\n",
+                            wipe_target.x, wipe_target.y, extruder_position, retraction_speed
+                        )?;
+                        if z_hop > 0. {
+                            write!(
+                                out,
+                                "G1 Z{:.6} F{:.0}\n",
+                                prev_path.z_height + z_hop,
+                                travel_speed
+                            )?;
+                        }
+                        let route = if avoid_crossing_perimeters {
+                            route_travel(wipe_target, first, &current_layer_perimeters)
+                        } else {
+                            vec![wipe_target, first]
+                        };
+                        for waypoint in route.iter().skip(1) {
+                            write!(
+                                out,
+                                "G1 X{:.6} Y{:.6} F{:.0}\n",
+                                waypoint.x, waypoint.y, travel_speed
+                            )?;
+                        }
+                        extruder_position += retraction_length;
+                        write!(
+                            out,
+                            "G1 E{:.6} F{:.0}\n",
+                            extruder_position, retraction_speed
+                        )?;
+                    }
+                }
+                // Very first extruded path in the whole file: the nozzle is wherever
+                // the start G-code left it (home/park), not at `first`, and there's
+                // nothing printed yet to retract or wipe from. Just move it there.
+                None => {
+                    write!(out, "G1 X{:.6} Y{:.6} F{:.0}\n", first.x, first.y, travel_speed)?;
+                }
+            }
+            // Mark support paths in the G-code so a post-processor (or a human reading
+            // the file) can tell them apart from the object itself.
+            if path.layer_type == LayerType::Support {
+                write!(out, ";TYPE:SUPPORT\n")?;
+            }
+            // Bridges print at their own fixed speed and full fan, regardless of the
+            // layer's cooling slowdown, so restore the layer's fan speed afterward.
+            let is_bridge = path.bridge_diameter.is_some();
+            if is_bridge {
+                write!(out, "M106 S255\n")?;
+            }
+            let feedrate = if is_bridge {
+                nominal_feedrate(path, is_first_layer, cooling)
+            } else {
+                (nominal_feedrate(path, is_first_layer, cooling) * slowdown)
+                    .max(cooling.min_print_speed)
+            };
+            path.write_gcode(out, &mut extruder_position, feedrate)?;
+            if is_bridge {
+                write!(out, "M106 S{:.0}\n", layer_fan_speed)?;
+            }
+            if path.is_perimeter {
+                current_layer_perimeters.push(path.paths.clone());
+            }
+            previous = Some(path);
+        }
+    }
+    Ok(())
+}
+
+// Evaluates a single template token: a numeric literal, or else a named variable
+// looked up from `variables`.
+fn eval_template_token(token: &str, variables: &HashMap<String, f64>) -> f64 {
+    match token.parse::<f64>() {
+        Ok(value) => value,
+        Err(_) => *variables
+            .get(token)
+            .unwrap_or_else(|| panic!("Unknown template variable: {}", token)),
+    }
+}
+
+// Renders a start/end G-code template: copies the text through verbatim, except for
+// `{...}` placeholders, which hold either a bare variable/number or a left-to-right
+// chain of `+ - * /` operations over them (e.g. `{first_layer_height * 2}`).
+fn render_gcode_template(template: &str, variables: &HashMap<String, f64>) -> String {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let after_open = &rest[start + 1..];
+        let end = after_open
+            .find('}')
+            .expect("unterminated template placeholder");
+        let expression = &after_open[..end];
+        let mut tokens = expression.split_whitespace();
+        let mut value = eval_template_token(
+            tokens.next().expect("empty template placeholder"),
+            variables,
+        );
+        while let Some(op) = tokens.next() {
+            let rhs = eval_template_token(
+                tokens
+                    .next()
+                    .expect("operator with no right-hand side in template placeholder"),
+                variables,
+            );
+            value = match op {
+                "+" => value + rhs,
+                "-" => value - rhs,
+                "*" => value * rhs,
+                "/" => value / rhs,
+                _ => panic!("Unknown template operator: {}", op),
+            };
+        }
+        // Whole numbers (temperatures, layer counts, ...) look better without a
+        // trailing ".0" in the emitted G-code.
+        if value.fract() == 0. {
+            rendered.push_str(&format!("{}", value as i64));
+        } else {
+            rendered.push_str(&format!("{}", value));
+        }
+        rest = &after_open[end + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
 #[derive(Debug)]
 struct Slicer {
     object_tree: Tree,
+    // `object_tree` imported into its own `Context` once up front, so `eval_object_sdf`
+    // (called per vertex/segment of every layer) evaluates against an existing graph
+    // instead of re-importing the whole CSG tree on every call.
+    object_eval_context: Context,
+    object_eval_node: Node,
     nozzle_diameter: f64,
     layer_height: f64,
     filament_diameter: f64,
     extrusion_width_scalar: f64,
     perimeters: u64,
+    infill_density: f64,
+    infill_type: InfillType,
+    infill_angle: f64,
+    retraction_length: f64,
+    retraction_speed: f64,
+    z_hop: f64,
+    travel_speed: f64,
+    avoid_crossing_perimeters: bool,
+    support_threshold_angle: f64,
+    support_density: f64,
+    support_z_gap: f64,
+    perimeter_speed: f64,
+    infill_speed: f64,
+    first_layer_speed: f64,
+    min_layer_time: f64,
+    min_print_speed: f64,
+    fan_speed: f64,
+    bridge_flow_ratio: f64,
+    bridge_speed: f64,
+    seam_position: SeamPosition,
+    nozzle_temperature: f64,
+    bed_temperature: f64,
+    start_gcode_template: String,
+    end_gcode_template: String,
     x_min: f64,
     x_max: f64,
     y_min: f64,
@@ -156,6 +652,30 @@ impl Slicer {
         filament_diameter: f64,
         extrusion_width_scalar: f64,
         perimeters: u64,
+        infill_density: f64,
+        infill_type: InfillType,
+        infill_angle: f64,
+        retraction_length: f64,
+        retraction_speed: f64,
+        z_hop: f64,
+        travel_speed: f64,
+        avoid_crossing_perimeters: bool,
+        support_threshold_angle: f64,
+        support_density: f64,
+        support_z_gap: f64,
+        perimeter_speed: f64,
+        infill_speed: f64,
+        first_layer_speed: f64,
+        min_layer_time: f64,
+        min_print_speed: f64,
+        fan_speed: f64,
+        bridge_flow_ratio: f64,
+        bridge_speed: f64,
+        seam_position: SeamPosition,
+        nozzle_temperature: f64,
+        bed_temperature: f64,
+        start_gcode_template: String,
+        end_gcode_template: String,
         x_min: f64,
         x_max: f64,
         y_min: f64,
@@ -163,13 +683,41 @@ impl Slicer {
         z_min: f64,
         z_max: f64,
     ) -> Slicer {
+        let mut object_eval_context = Context::new();
+        let object_eval_node = object_eval_context.import(&tree);
         Slicer {
             object_tree: tree,
+            object_eval_context: object_eval_context,
+            object_eval_node: object_eval_node,
             nozzle_diameter: nozzle_diameter,
             layer_height: layer_height,
             filament_diameter: filament_diameter,
             extrusion_width_scalar: extrusion_width_scalar,
             perimeters: perimeters,
+            infill_density: infill_density,
+            infill_type: infill_type,
+            infill_angle: infill_angle,
+            retraction_length: retraction_length,
+            retraction_speed: retraction_speed,
+            z_hop: z_hop,
+            travel_speed: travel_speed,
+            avoid_crossing_perimeters: avoid_crossing_perimeters,
+            support_threshold_angle: support_threshold_angle,
+            support_density: support_density,
+            support_z_gap: support_z_gap,
+            perimeter_speed: perimeter_speed,
+            infill_speed: infill_speed,
+            first_layer_speed: first_layer_speed,
+            min_layer_time: min_layer_time,
+            min_print_speed: min_print_speed,
+            fan_speed: fan_speed,
+            bridge_flow_ratio: bridge_flow_ratio,
+            bridge_speed: bridge_speed,
+            seam_position: seam_position,
+            nozzle_temperature: nozzle_temperature,
+            bed_temperature: bed_temperature,
+            start_gcode_template: start_gcode_template,
+            end_gcode_template: end_gcode_template,
             x_min: x_min,
             x_max: x_max,
             y_min: y_min,
@@ -179,7 +727,572 @@ impl Slicer {
         }
     }
 
-    fn slice(&mut self) {
+    // Pulled out of the perimeter loop so the infill pass below can reuse the exact same
+    // octree -> mesh -> edge-walk pipeline instead of duplicating it.
+    fn walk_layer_tree(
+        &self,
+        tree: Tree,
+        debug_label: &str,
+        debug_z: f64,
+        path_z_height: f64,
+        path_width: f64,
+        extruder_cross_sectional_area_per_mm: f64,
+        is_perimeter: bool,
+        variable_width: Option<f64>,
+        layer_type: LayerType,
+    ) -> Vec<ExtrusionPath> {
+        let mut paths = Vec::<ExtrusionPath>::new();
+        // When set, each point's width is sampled from the local object thickness at
+        // this layer's Z (the `f64`) instead of the nominal `path_width`.
+        let width_at = |point: Vector2<f32>| -> f64 {
+            match variable_width {
+                Some(layer_z_height) => self.local_bead_width(point, layer_z_height, path_width),
+                None => path_width,
+            }
+        };
+
+        let mut context = Context::new();
+        let node = context.import(&tree);
+        let vmdata = VmData::<255>::new(&context, &[node]).unwrap();
+        let temp_vmdata = fs::File::create(format!(
+            "debug_data/vmdata_{}_{:.2}.bin",
+            debug_label, debug_z
+        ))
+        .unwrap();
+        bincode::serialize_into(temp_vmdata, &vmdata);
+        let exported_tree = context.export(node).expect("No Mr. Bond, I expect a tree.");
+        let shape = JitShape::from(exported_tree);
+        let mut temp_settings = fs::File::create(format!(
+            "debug_data/settings_{}_{:.2}",
+            debug_label, debug_z
+        ))
+        .unwrap();
+        write!(&mut temp_settings, "depth: {}\n", 8);
+        write!(
+            &mut temp_settings,
+            "center x: {}\n",
+            (((self.x_max + self.x_min) / 2.) as f32)
+        );
+        write!(
+            &mut temp_settings,
+            "center y: {}\n",
+            (((self.y_max + self.y_min) / 2.) as f32)
+        );
+        write!(&mut temp_settings, "center z: {}\n", (0. as f32));
+        write!(
+            &mut temp_settings,
+            "size: {}\n",
+            (((self.x_max - self.x_min).max(self.y_max - self.y_min) + EPS) as f32)
+        );
+        let octree_settings = Settings {
+            depth: 8,
+            // TODO: fix bounds
+            bounds: Bounds {
+                center: Vector3::new(
+                    ((self.x_max + self.x_min) / 2.) as f32,
+                    ((self.y_max + self.y_min) / 2.) as f32,
+                    0.,
+                ),
+                size: ((self.x_max - self.x_min).max(self.y_max - self.y_min) + EPS) as f32,
+            },
+            ..Default::default()
+        };
+        let o = Octree::build(&shape, octree_settings);
+        // Produce a mesh that contains a path that we will extract to use as the
+        // toolpath. I know this is doing a huge amount more computation than
+        // needed for this task, this is a proof of concept.
+        let mesh = o.walk_dual(octree_settings);
+        let mut temp_stl = fs::File::create(format!(
+            "debug_data/temp_{}_{:.2}.stl",
+            debug_label, debug_z
+        ))
+        .unwrap();
+        mesh.write_stl(&mut temp_stl);
+        // Extract path from mesh. Iterate over all triangles. This would not be
+        // necissary if the result was 2D; maybe ask fidget to support it.
+        let mut edge_map_as_bits = IndexMap::new();
+        // Filter triangles to only those that contain two vertices on the current
+        // layer - this subset of triangles must contain the paths.
+        for triangle in mesh.triangles.iter().filter(|tri| {
+            let num_vertices_at_layer: u8 = (0..=2)
+                .map(|i| ((mesh.vertices[tri[i]].z as f64).abs() < EPS) as u8)
+                .sum();
+            num_vertices_at_layer == 2
+        }) {
+            // Append any edges that lie in the same plane as the current layer.
+            for (edge_0_index, edge_1_index) in vec![
+                (triangle[0], triangle[1]),
+                (triangle[1], triangle[2]),
+                (triangle[2], triangle[0]),
+            ]
+            .into_iter()
+            {
+                if ((mesh.vertices[edge_0_index].z as f64).abs()) < EPS
+                    && ((mesh.vertices[edge_1_index].z as f64).abs()) < EPS
+                {
+                    edge_map_as_bits.insert(
+                        [
+                            mesh.vertices[edge_0_index].x.to_bits(),
+                            mesh.vertices[edge_0_index].y.to_bits(),
+                        ],
+                        [
+                            mesh.vertices[edge_1_index].x.to_bits(),
+                            mesh.vertices[edge_1_index].y.to_bits(),
+                        ],
+                    );
+                }
+            }
+        }
+        // This whole block of code disgusts me. It could be reordered to be more
+        // concise, but eh.
+        match edge_map_as_bits.first() {
+            Some((key, _)) => {
+                let start_point = Vector2::new(f32::from_bits(key[0]), f32::from_bits(key[1]));
+                let mut curr_path = ExtrusionPath::new(
+                    width_at(start_point),
+                    self.layer_height,
+                    path_z_height,
+                    extruder_cross_sectional_area_per_mm,
+                    start_point,
+                    is_perimeter,
+                    layer_type,
+                    None,
+                );
+                while !edge_map_as_bits.is_empty() {
+                    // unwrap should be fine, these always have at least one value
+                    // in the path.
+                    let last_point = curr_path.last_point_in_path_as_bits().unwrap();
+                    let next_point = edge_map_as_bits.swap_remove(&last_point);
+                    match next_point {
+                        Some(p) => {
+                            let point = Vector2::new(f32::from_bits(p[0]), f32::from_bits(p[1]));
+                            curr_path.add_to_path(point, width_at(point));
+                            if edge_map_as_bits.is_empty() {
+                                // TODO: get rid of this clone; memswap?
+                                paths.push(curr_path.clone());
+                            }
+                        }
+                        None => {
+                            // TODO: get rid of this clone; memswap?
+                            paths.push(curr_path.clone());
+                            match edge_map_as_bits.first() {
+                                Some((key, _)) => {
+                                    let start_point = Vector2::new(
+                                        f32::from_bits(key[0]),
+                                        f32::from_bits(key[1]),
+                                    );
+                                    curr_path = ExtrusionPath::new(
+                                        width_at(start_point),
+                                        self.layer_height,
+                                        path_z_height,
+                                        extruder_cross_sectional_area_per_mm,
+                                        start_point,
+                                        is_perimeter,
+                                        layer_type,
+                                        None,
+                                    );
+                                }
+                                // map is empty and loop will break
+                                None => (),
+                            }
+                        }
+                    }
+                }
+            }
+            None => (),
+        }
+        paths
+    }
+
+    // Scalar evaluation of the raw object SDF at a single point, for the variable-width
+    // perimeter thickness estimate below. Reuses the `Context`/`Node` cached on `self`
+    // at construction time rather than re-importing `object_tree` on every call - this
+    // runs once per vertex or segment of every layer, so re-importing would make
+    // anything past a toy model impractically slow.
+    fn eval_object_sdf(&self, x: f64, y: f64, z: f64) -> f64 {
+        self.object_eval_context
+            .eval_xyz(self.object_eval_node, x, y, z)
+            .unwrap_or(0.)
+    }
+
+    // Arachne-style variable width: approximate the local wall thickness at a
+    // perimeter centerline point as twice the raw object SDF magnitude there (a point
+    // straddling the medial axis of a thin feature sits roughly equidistant from both
+    // of the feature's surfaces), then decide how the single innermost bead should
+    // absorb it. Below one nominal bead width, the remaining gap can't fit even one
+    // full bead, so beads merge into the narrowest one this printer can extrude
+    // instead of thinning toward zero. Between one and two bead widths, a single bead
+    // widens to cover the whole gap rather than leaving part of it unprinted. Above
+    // two bead widths there's room for another fixed-width ring to pick up the slack,
+    // so this bead is capped at twice nominal rather than ballooning further.
+    fn local_bead_width(
+        &self,
+        point: Vector2<f32>,
+        layer_z_height: f64,
+        nominal_width: f64,
+    ) -> f64 {
+        let thickness = 2.
+            * self
+                .eval_object_sdf(point.x as f64, point.y as f64, layer_z_height)
+                .abs();
+        let min_width = nominal_width / 2.;
+        let max_width = nominal_width * 2.;
+        if thickness < nominal_width {
+            min_width
+        } else {
+            thickness.min(max_width)
+        }
+    }
+
+    // True if there's no solid a full layer height below a segment's midpoint, i.e.
+    // this segment would be printed over thin air rather than onto the layer below.
+    fn segment_is_unsupported(&self, a: Vector2<f32>, b: Vector2<f32>, z_height: f64) -> bool {
+        let midpoint = (a + b) / 2.;
+        self.eval_object_sdf(
+            midpoint.x as f64,
+            midpoint.y as f64,
+            z_height - self.layer_height,
+        ) > 0.
+    }
+
+    // Collapses a bridge piece down to a straight line between its two anchor points,
+    // discarding whatever intermediate vertices the original contour/fill line had.
+    // This is what aligns the extrusion direction with the span: a multi-vertex chunk
+    // of a perimeter or an infill line crossing a gap at a shallow angle would
+    // otherwise still wander along the original geometry instead of running the
+    // shortest, tautest path between the two anchors.
+    fn straighten_bridge_piece(path: &mut ExtrusionPath) {
+        if path.bridge_diameter.is_none() || path.paths.len() < 3 {
+            return;
+        }
+        let first = *path.paths.first().unwrap();
+        let last = *path.paths.last().unwrap();
+        let first_width = *path.path_width.first().unwrap();
+        let last_width = *path.path_width.last().unwrap();
+        path.paths = vec![first, last];
+        path.path_width = vec![first_width, last_width];
+    }
+
+    // Splits `path` at every transition between supported and unsupported segments, so
+    // a contiguous unsupported run can be re-extruded as a bridge: round filament flow
+    // (no layer below to squash the bead against) at `--bridge-speed`, straightened to
+    // run directly between its two anchors (see `straighten_bridge_piece`) so the
+    // strand is pulled taut rather than sagging along whatever path the original
+    // contour or fill line took through the gap.
+    fn split_bridge_segments(&self, path: &ExtrusionPath, bridge_diameter: f64) -> Vec<ExtrusionPath> {
+        if path.paths.len() < 2 {
+            return vec![path.clone()];
+        }
+        let mut split_paths = Vec::new();
+        let mut current = path.clone();
+        current.paths.truncate(1);
+        current.path_width.truncate(1);
+        current.bridge_diameter = None;
+        for i in 0..path.paths.len() - 1 {
+            let unsupported = self.segment_is_unsupported(path.paths[i], path.paths[i + 1], path.z_height);
+            let bridging = unsupported.then_some(bridge_diameter);
+            if i == 0 {
+                current.bridge_diameter = bridging;
+            } else if bridging != current.bridge_diameter {
+                Self::straighten_bridge_piece(&mut current);
+                split_paths.push(current.clone());
+                current = path.clone();
+                current.paths.truncate(1);
+                current.paths[0] = path.paths[i];
+                current.path_width.truncate(1);
+                current.path_width[0] = path.path_width[i];
+                current.bridge_diameter = bridging;
+            }
+            current.paths.push(path.paths[i + 1]);
+            current.path_width.push(path.path_width[i + 1]);
+        }
+        Self::straighten_bridge_piece(&mut current);
+        split_paths.push(current);
+        split_paths
+    }
+
+    // Central-difference gradient of the raw object SDF, normalized. Its direction is
+    // the local outward surface normal - the same quantity fidget's own gradient
+    // evaluation would give us, just computed by reusing `eval_object_sdf` rather than
+    // depending on a second evaluation path.
+    fn eval_object_normal(&self, x: f64, y: f64, z: f64) -> Vector3<f64> {
+        let h = 1e-3;
+        let gradient = Vector3::new(
+            self.eval_object_sdf(x + h, y, z) - self.eval_object_sdf(x - h, y, z),
+            self.eval_object_sdf(x, y + h, z) - self.eval_object_sdf(x, y - h, z),
+            self.eval_object_sdf(x, y, z + h) - self.eval_object_sdf(x, y, z - h),
+        );
+        let norm = gradient.norm();
+        if norm < 1e-9 {
+            Vector3::new(0., 0., 1.)
+        } else {
+            gradient / norm
+        }
+    }
+
+    // Picks which vertex of a closed perimeter loop the seam should start at, per
+    // `self.seam_position`. `previous_end` is wherever the nozzle finished the
+    // previous path, used by `Nearest`.
+    fn seam_index_for(&self, path: &ExtrusionPath, previous_end: Option<Vector2<f32>>) -> usize {
+        let n = path.paths.len();
+        // Closed loop: last point duplicates the first, so candidates are 0..n-1.
+        if n < 4 {
+            return 0;
+        }
+        let vertices = &path.paths[..n - 1];
+        match self.seam_position {
+            SeamPosition::Rear => (0..vertices.len())
+                .max_by(|&a, &b| vertices[a].y.partial_cmp(&vertices[b].y).unwrap())
+                .unwrap_or(0),
+            SeamPosition::Nearest => {
+                let reference = match previous_end {
+                    Some(p) => p,
+                    None => return 0,
+                };
+                (0..vertices.len())
+                    .min_by(|&a, &b| {
+                        (vertices[a] - reference)
+                            .norm()
+                            .partial_cmp(&(vertices[b] - reference).norm())
+                            .unwrap()
+                    })
+                    .unwrap_or(0)
+            }
+            SeamPosition::Aligned => {
+                // Weight chosen so a few mm of extra distance from the reference point
+                // is worth escaping an obviously convex corner, without overriding a
+                // clearly concave one found much farther away.
+                let distance_penalty = 0.1;
+                let reference = Vector2::new(self.x_min as f32, self.y_min as f32);
+                (0..vertices.len())
+                    .min_by(|&a, &b| {
+                        let score = |i: usize| -> f64 {
+                            let prev = vertices[(i + vertices.len() - 1) % vertices.len()];
+                            let curr = vertices[i];
+                            let next = vertices[(i + 1) % vertices.len()];
+                            let turn = exterior_turn_angle(prev, curr, next);
+                            let distance = (curr - reference).norm() as f64;
+                            turn + distance_penalty * distance
+                        };
+                        score(a).partial_cmp(&score(b)).unwrap()
+                    })
+                    .unwrap_or(0)
+            }
+        }
+    }
+
+    // The object offset inward by the full perimeter stack, i.e. the region the
+    // perimeters leave behind for infill to fill.
+    fn inner_region_tree(&self, layer_z_height: f64, path_spacing: f64) -> Tree {
+        self.object_tree
+            .clone()
+            .remap_xyz(Tree::x(), Tree::y(), Tree::constant(layer_z_height))
+            + path_spacing * (self.perimeters as f64)
+    }
+
+    // The region at this layer that needs support material underneath it: the
+    // silhouette of the object at every height above this layer (projected straight
+    // down, as if support were built up from the build plate), minus the object
+    // itself at this layer (inflated outward by the support air gap so the support
+    // doesn't fuse to it). Returns `None` when there's nothing above this layer to
+    // support in the first place.
+    //
+    // Not cheap - it folds together the object's cross-section at every layer above
+    // the current one - but this is a proof of concept.
+    fn support_region_tree(
+        &self,
+        layer_mid_heights: &[f64],
+        layer_z_height: f64,
+        layer_box: &Tree,
+    ) -> Option<Tree> {
+        let silhouette_above = layer_mid_heights
+            .iter()
+            .filter(|&&z| z > layer_z_height)
+            .map(|&z| {
+                self.object_tree
+                    .clone()
+                    .remap_xyz(Tree::x(), Tree::y(), Tree::constant(z))
+            })
+            .reduce(|a, b| a.min(b))?;
+        let this_layer_inflated = self.object_tree.clone().remap_xyz(
+            Tree::x(),
+            Tree::y(),
+            Tree::constant(layer_z_height),
+        ) - self.support_z_gap;
+        Some(
+            silhouette_above
+                .max(-this_layer_inflated)
+                .max(layer_box.clone()),
+        )
+    }
+
+    // Build the infill shell tree for the current layer: a triply-periodic minimal
+    // surface (gyroid) thickened into a thin shell, intersected with the interior
+    // region of the object left over once the perimeters are subtracted.
+    fn gyroid_infill_tree(&self, layer_z_height: f64, path_spacing: f64, layer_box: &Tree) -> Tree {
+        // Cell size of the TPMS lattice; phase is continuous in Z, so
+        // consecutive layers naturally interlock without extra bookkeeping.
+        let gyroid_cell_size = 8. * self.nozzle_diameter;
+        let p = gyroid_cell_size / (2. * PI);
+        let gyroid = (Tree::x() / p).sin() * (Tree::y() / p).cos()
+            + (Tree::y() / p).sin() * (Tree::z() / p).cos()
+            + (Tree::z() / p).sin() * (Tree::x() / p).cos();
+        // Density maps to wall thickness of the gyroid shell, tied to the
+        // extrusion width so 0..1 spans "nothing" to "roughly solid".
+        let extrusion_width = self.extrusion_width_scalar * self.nozzle_diameter;
+        let wall = extrusion_width * self.infill_density;
+        let gyroid_shell = gyroid
+            .remap_xyz(Tree::x(), Tree::y(), Tree::constant(layer_z_height))
+            .abs()
+            - wall;
+        let inner_region = self.inner_region_tree(layer_z_height, path_spacing);
+        gyroid_shell.max(inner_region).max(layer_box.clone())
+    }
+
+    // Rasterize the interior polygons (closed loops, as returned by walk_layer_tree on
+    // the inner-region tree) into parallel line segments at `angle_degrees`, spaced
+    // `spacing` apart, using an even-odd scanline fill.
+    fn rectilinear_infill_paths(
+        &self,
+        boundary_paths: &[ExtrusionPath],
+        angle_degrees: f64,
+        spacing: f64,
+        path_z_height: f64,
+        path_width: f64,
+        extruder_cross_sectional_area_per_mm: f64,
+        layer_type: LayerType,
+    ) -> Vec<ExtrusionPath> {
+        let theta = angle_degrees.to_radians();
+        let (sin_t, cos_t) = theta.sin_cos();
+        // Rotate every boundary vertex into fill space, so scanlines can sweep along u.
+        let rotated: Vec<Vec<Vector2<f64>>> = boundary_paths
+            .iter()
+            .map(|loop_path| {
+                loop_path
+                    .paths
+                    .iter()
+                    .map(|v| {
+                        let x = v.x as f64;
+                        let y = v.y as f64;
+                        Vector2::new(x * cos_t + y * sin_t, -x * sin_t + y * cos_t)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut paths = Vec::<ExtrusionPath>::new();
+        let all_u = rotated.iter().flatten().map(|v| v.x);
+        let u_min = match all_u.clone().fold(None, |acc: Option<f64>, x| {
+            Some(acc.map_or(x, |m| m.min(x)))
+        }) {
+            Some(m) => m,
+            None => return paths,
+        };
+        let u_max = all_u
+            .fold(None, |acc: Option<f64>, x| {
+                Some(acc.map_or(x, |m| m.max(x)))
+            })
+            .unwrap();
+
+        let rotate_back = |u: f64, v: f64| -> Vector2<f32> {
+            Vector2::new(
+                (u * cos_t - v * sin_t) as f32,
+                (u * sin_t + v * cos_t) as f32,
+            )
+        };
+
+        let mut scanline_index = 0i64;
+        let mut u = u_min;
+        while u <= u_max {
+            // Intersect the scanline u = const with every polygon edge, even-odd rule.
+            let mut crossings = Vec::<f64>::new();
+            for loop_pts in &rotated {
+                if loop_pts.len() < 2 {
+                    continue;
+                }
+                for (a, b) in loop_pts.iter().zip(loop_pts.iter().skip(1)) {
+                    if (a.x <= u) != (b.x <= u) {
+                        let t = (u - a.x) / (b.x - a.x);
+                        crossings.push(a.y + t * (b.y - a.y));
+                    }
+                }
+            }
+            crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            // Alternate which end of the span we start from so consecutive scanlines
+            // connect at a shared end, cutting travel between them.
+            let flip = scanline_index % 2 != 0;
+            for pair in crossings.chunks(2) {
+                if pair.len() < 2 {
+                    continue;
+                }
+                let (v_start, v_end) = if flip {
+                    (pair[1], pair[0])
+                } else {
+                    (pair[0], pair[1])
+                };
+                let mut path = ExtrusionPath::new(
+                    path_width,
+                    self.layer_height,
+                    path_z_height,
+                    extruder_cross_sectional_area_per_mm,
+                    rotate_back(u, v_start),
+                    false,
+                    layer_type,
+                    None,
+                );
+                path.add_to_path(rotate_back(u, v_end), path_width);
+                paths.push(path);
+            }
+            u += spacing;
+            scanline_index += 1;
+        }
+        paths
+    }
+
+    // Variables available to start/end G-code templates: every scalar config field,
+    // plus `layer_count` and `first_layer_z`, computed once slicing has decided them.
+    // `first_layer_height` is an alias for `layer_height` - this slicer doesn't (yet)
+    // support a distinct first-layer height, but templates conventionally expect the
+    // name.
+    fn template_variables(&self, layer_count: usize) -> HashMap<String, f64> {
+        let mut variables = HashMap::new();
+        variables.insert("nozzle_diameter".to_string(), self.nozzle_diameter);
+        variables.insert("layer_height".to_string(), self.layer_height);
+        variables.insert("first_layer_height".to_string(), self.layer_height);
+        variables.insert("filament_diameter".to_string(), self.filament_diameter);
+        variables.insert("extrusion_width_scalar".to_string(), self.extrusion_width_scalar);
+        variables.insert("perimeters".to_string(), self.perimeters as f64);
+        variables.insert("infill_density".to_string(), self.infill_density);
+        variables.insert("infill_angle".to_string(), self.infill_angle);
+        variables.insert("retraction_length".to_string(), self.retraction_length);
+        variables.insert("retraction_speed".to_string(), self.retraction_speed);
+        variables.insert("z_hop".to_string(), self.z_hop);
+        variables.insert("travel_speed".to_string(), self.travel_speed);
+        variables.insert("support_threshold_angle".to_string(), self.support_threshold_angle);
+        variables.insert("support_density".to_string(), self.support_density);
+        variables.insert("support_z_gap".to_string(), self.support_z_gap);
+        variables.insert("perimeter_speed".to_string(), self.perimeter_speed);
+        variables.insert("infill_speed".to_string(), self.infill_speed);
+        variables.insert("first_layer_speed".to_string(), self.first_layer_speed);
+        variables.insert("min_layer_time".to_string(), self.min_layer_time);
+        variables.insert("min_print_speed".to_string(), self.min_print_speed);
+        variables.insert("fan_speed".to_string(), self.fan_speed);
+        variables.insert("bridge_flow_ratio".to_string(), self.bridge_flow_ratio);
+        variables.insert("bridge_speed".to_string(), self.bridge_speed);
+        variables.insert("nozzle_temperature".to_string(), self.nozzle_temperature);
+        variables.insert("bed_temperature".to_string(), self.bed_temperature);
+        variables.insert("x_min".to_string(), self.x_min);
+        variables.insert("x_max".to_string(), self.x_max);
+        variables.insert("y_min".to_string(), self.y_min);
+        variables.insert("y_max".to_string(), self.y_max);
+        variables.insert("z_min".to_string(), self.z_min);
+        variables.insert("z_max".to_string(), self.z_max);
+        variables.insert("layer_count".to_string(), layer_count as f64);
+        variables.insert("first_layer_z".to_string(), self.z_min + self.layer_height);
+        variables
+    }
+
+    fn slice(&mut self) -> String {
         let z_range = self.z_max - self.z_min;
 
         let layer_count = z_range / self.layer_height;
@@ -196,10 +1309,23 @@ impl Slicer {
         }
 
         let extruder_cross_sectional_area_per_mm = PI * (self.filament_diameter / 2.).powi(2);
+        // Every layer's mid-height, needed up front so support generation for a given
+        // layer can see what the object looks like above it.
+        let layer_mid_heights: Vec<f64> = layers
+            .iter()
+            .map(|l| l.z_height + self.layer_height / 2.)
+            .collect();
 
-        let mut extrusion_paths = Vec::<ExtrusionPath>::new();
-        for layer in layers {
+        // Buffered per layer (rather than one flat list) so the cooling pass in
+        // `emit_gcode` can see an entire layer's paths before picking its feedrates.
+        let mut layered_paths = Vec::<Vec<ExtrusionPath>>::new();
+        for (layer_index, layer) in layers.into_iter().enumerate() {
+            let mut current_layer_paths = Vec::<ExtrusionPath>::new();
             match layer.layer_type {
+                LayerType::Support => {
+                    // Support toolpaths are tagged per-`ExtrusionPath` rather than
+                    // represented as their own `Layer`s; see the `Standard` arm below.
+                }
                 LayerType::Standard => {
                     let extrusion_width = self.extrusion_width_scalar * self.nozzle_diameter;
                     let path_spacing = extrusion_width - self.layer_height * (1. - PI / 4.);
@@ -208,180 +1334,187 @@ impl Slicer {
                     let layer_z_height = layer.z_height + self.layer_height / 2.;
                     let layer_box =
                         bounded_box(self.x_min, self.y_min, 0., self.x_max, self.y_max, 2.);
+                    // Diameter of a bridge bead: a round filament cross-section rather than
+                    // the usual squished one, since there's no layer below to squash against.
+                    let bridge_diameter = self.nozzle_diameter * self.bridge_flow_ratio;
+                    // Support has to go down before the perimeters that will sit on top of it.
+                    if self.support_density > 0. {
+                        if let Some(support_tree) =
+                            self.support_region_tree(&layer_mid_heights, layer_z_height, &layer_box)
+                        {
+                            let support_boundary = self.walk_layer_tree(
+                                support_tree,
+                                "support_boundary",
+                                layer.z_height,
+                                layer.z_height + self.layer_height,
+                                extrusion_width,
+                                extruder_cross_sectional_area_per_mm,
+                                false,
+                                None,
+                                LayerType::Standard,
+                            );
+                            let support_spacing = extrusion_width / self.support_density;
+                            let mut support_paths = self.rectilinear_infill_paths(
+                                &support_boundary,
+                                45.,
+                                support_spacing,
+                                layer.z_height + self.layer_height,
+                                extrusion_width,
+                                extruder_cross_sectional_area_per_mm,
+                                LayerType::Support,
+                            );
+                            // Keep only the spans actually underneath a sufficiently
+                            // steep overhang, per the gradient of the object's surface
+                            // one layer up from here.
+                            support_paths.retain(|path| {
+                                let midpoint =
+                                    (path.paths[0] + path.paths[path.paths.len() - 1]) / 2.;
+                                let normal = self.eval_object_normal(
+                                    midpoint.x as f64,
+                                    midpoint.y as f64,
+                                    layer_z_height + self.layer_height,
+                                );
+                                overhang_angle_degrees(normal) > self.support_threshold_angle
+                            });
+                            current_layer_paths.extend(support_paths);
+                        }
+                    }
                     // Subtract a multiple of extrusion widths from the object, then intersect it
                     // with the tree.
                     for perimeter in (0..self.perimeters).rev() {
                         // this cloning might be slow, unsure if this is an Arc or not
-                        let mut perimeter_tree = (self.object_tree.clone().remap_xyz(
+                        let perimeter_tree = (self.object_tree.clone().remap_xyz(
                             Tree::x(),
                             Tree::y(),
                             Tree::constant(layer_z_height),
-                        ) + path_spacing
-                            * ((perimeter as f64) + 1. / 2.))
+                        ) + path_spacing * ((perimeter as f64) + 1. / 2.))
                             .max(layer_box.clone());
-                        let mut perimeter_context = Context::new();
-                        let perimeter_node = perimeter_context.import(&perimeter_tree);
-                        let perimeter_vmdata =
-                            VmData::<255>::new(&perimeter_context, &[perimeter_node]).unwrap();
-                        let mut temp_vmdata = fs::File::create(format!(
-                            "debug_data/vmdata_{:.2}.bin",
-                            layer.z_height
-                        ))
-                        .unwrap();
-                        bincode::serialize_into(temp_vmdata, &perimeter_vmdata);
-                        perimeter_tree = perimeter_context
-                            .export(perimeter_node)
-                            .expect("No Mr. Bond, I expect a tree.");
-                        let perimeter_shape = JitShape::from(perimeter_tree);
-                        let mut temp_settings = fs::File::create(format!(
-                            "debug_data/settings_{:.2}",
-                            layer.z_height
-                        ))
-                        .unwrap();
-                        write!(&mut temp_settings, "depth: {}\n", 8);
-                        write!(
-                            &mut temp_settings,
-                            "center x: {}\n",
-                            (((self.x_max + self.x_min) / 2.) as f32)
+                        // Only the innermost ring borders potentially-thin or tapering
+                        // features, so that's the one that gets variable width.
+                        let variable_width = is_innermost_perimeter(perimeter, self.perimeters)
+                            .then_some(layer_z_height);
+                        let mut perimeter_loops = self.walk_layer_tree(
+                            perimeter_tree,
+                            "perimeter",
+                            layer.z_height,
+                            layer.z_height + self.layer_height,
+                            extrusion_width,
+                            extruder_cross_sectional_area_per_mm,
+                            true,
+                            variable_width,
+                            LayerType::Standard,
                         );
-                        write!(
-                            &mut temp_settings,
-                            "center y: {}\n",
-                            (((self.y_max + self.y_min) / 2.) as f32)
-                        );
-                        write!(
-                            &mut temp_settings,
-                            "center z: {}\n",
-                            (0. as f32)
-                        );
-                        write!(
-                            &mut temp_settings,
-                            "size: {}\n",
-                            (((self.x_max - self.x_min).max(self.y_max - self.y_min) + EPS) as f32)
-                        );
-                        let perimeter_octree_settings = Settings {
-                            depth: 8,
-                            // TODO: fix bounds
-                            bounds: Bounds {
-                                center: Vector3::new(
-                                    ((self.x_max + self.x_min) / 2.) as f32,
-                                    ((self.y_max + self.y_min) / 2.) as f32,
-                                    0.,
-                                ),
-                                size: ((self.x_max - self.x_min).max(self.y_max - self.y_min) + EPS)
-                                    as f32,
-                            },
-                            ..Default::default()
-                        };
-                        let o = Octree::build(&perimeter_shape, perimeter_octree_settings);
-                        // Produce a mesh that contains a path that we will extract to use as the
-                        // perimter path. I know this is doing a huge amount more computation than
-                        // needed for this task, this is a proof of concept.
-                        let perimeter_mesh = o.walk_dual(perimeter_octree_settings);
-                        let mut temp_stl =
-                            fs::File::create(format!("debug_data/temp_{:.2}.stl", layer.z_height))
-                                .unwrap();
-                        perimeter_mesh.write_stl(&mut temp_stl);
-                        // Extract path from mesh. Iterate over all triangles. This would not be
-                        // necissary if the result was 2D; maybe ask fidget to support it.
-                        let mut edge_map_as_bits = IndexMap::new();
-                        // Filter triangles to only those that contain two vertices on the current
-                        // layer - this subset of triangles must contain the paths.
-                        for triangle in perimeter_mesh.triangles.iter().filter(|tri| {
-                            let num_vertices_at_layer: u8 = (0..=2)
-                                .map(|i| {
-                                    ((perimeter_mesh.vertices[tri[i]].z as f64).abs() < EPS) as u8
-                                })
-                                .sum();
-                            num_vertices_at_layer == 2
-                        }) {
-                            // Append any edges that lie in the same plane as the current layer.
-                            for (edge_0_index, edge_1_index) in vec![
-                                (triangle[0], triangle[1]),
-                                (triangle[1], triangle[2]),
-                                (triangle[2], triangle[0]),
-                            ]
-                            .into_iter()
-                            {
-                                if ((perimeter_mesh.vertices[edge_0_index].z as f64).abs()) < EPS
-                                    && ((perimeter_mesh.vertices[edge_1_index].z as f64).abs())
-                                        < EPS
-                                {
-                                    edge_map_as_bits.insert(
-                                        [
-                                            perimeter_mesh.vertices[edge_0_index].x.to_bits(),
-                                            perimeter_mesh.vertices[edge_0_index].y.to_bits(),
-                                        ],
-                                        [
-                                            perimeter_mesh.vertices[edge_1_index].x.to_bits(),
-                                            perimeter_mesh.vertices[edge_1_index].y.to_bits(),
-                                        ],
-                                    );
+                        // Move each loop's start point to the chosen seam vertex
+                        // instead of leaving it wherever the edge-walk happened to
+                        // start (effectively random).
+                        let mut previous_end = current_layer_paths
+                            .last()
+                            .and_then(|p| p.last_point_in_path())
+                            .copied();
+                        for loop_path in perimeter_loops.iter_mut() {
+                            let seam_index = self.seam_index_for(loop_path, previous_end);
+                            rotate_closed_loop(loop_path, seam_index);
+                            previous_end = loop_path.last_point_in_path().copied();
+                        }
+                        // Re-extrude any span with nothing solid beneath it as a bridge,
+                        // instead of assuming every perimeter lands on the layer below.
+                        for loop_path in &perimeter_loops {
+                            current_layer_paths
+                                .extend(self.split_bridge_segments(loop_path, bridge_diameter));
+                        }
+                    }
+                    // Fill whatever the perimeters left behind with the configured infill
+                    // pattern, reusing the same octree -> mesh -> edge-walk pipeline.
+                    if self.infill_density > 0. {
+                        match self.infill_type {
+                            InfillType::Gyroid => {
+                                let infill_tree = self.gyroid_infill_tree(
+                                    layer_z_height,
+                                    path_spacing,
+                                    &layer_box,
+                                );
+                                let infill_paths = self.walk_layer_tree(
+                                    infill_tree,
+                                    "infill",
+                                    layer.z_height,
+                                    layer.z_height + self.layer_height,
+                                    extrusion_width,
+                                    extruder_cross_sectional_area_per_mm,
+                                    false,
+                                    None,
+                                    LayerType::Standard,
+                                );
+                                for path in &infill_paths {
+                                    current_layer_paths
+                                        .extend(self.split_bridge_segments(path, bridge_diameter));
                                 }
                             }
-                        }
-                        // This whole block of code disgusts me. It could be reordered to be more
-                        // concise, but eh.
-                        match edge_map_as_bits.first() {
-                            Some((key, _)) => {
-                                let mut curr_path = ExtrusionPath::new(
+                            InfillType::Rectilinear => {
+                                let inner_region = self
+                                    .inner_region_tree(layer_z_height, path_spacing)
+                                    .max(layer_box.clone());
+                                let boundary_paths = self.walk_layer_tree(
+                                    inner_region,
+                                    "infill_boundary",
+                                    layer.z_height,
+                                    layer.z_height + self.layer_height,
                                     extrusion_width,
-                                    self.layer_height,
+                                    extruder_cross_sectional_area_per_mm,
+                                    false,
+                                    None,
+                                    LayerType::Standard,
+                                );
+                                let infill_spacing = extrusion_width / self.infill_density;
+                                let angle = self.infill_angle + 90. * ((layer_index % 2) as f64);
+                                let infill_paths = self.rectilinear_infill_paths(
+                                    &boundary_paths,
+                                    angle,
+                                    infill_spacing,
                                     layer.z_height + self.layer_height,
+                                    extrusion_width,
                                     extruder_cross_sectional_area_per_mm,
-                                    Vector2::new(f32::from_bits(key[0]), f32::from_bits(key[1])),
+                                    LayerType::Standard,
                                 );
-                                while !edge_map_as_bits.is_empty() {
-                                    // unwrap should be fine, these always have at least one value
-                                    // in the path.
-                                    let last_point =
-                                        curr_path.last_point_in_path_as_bits().unwrap();
-                                    let next_point = edge_map_as_bits.swap_remove(&last_point);
-                                    match next_point {
-                                        Some(p) => {
-                                            curr_path.add_to_path(Vector2::new(
-                                                f32::from_bits(p[0]),
-                                                f32::from_bits(p[1]),
-                                            ));
-                                            if edge_map_as_bits.is_empty() {
-                                                // TODO: get rid of this clone; memswap?
-                                                extrusion_paths.push(curr_path.clone());
-                                            }
-                                        }
-                                        None => {
-                                            // TODO: get rid of this clone; memswap?
-                                            extrusion_paths.push(curr_path.clone());
-                                            match edge_map_as_bits.first() {
-                                                Some((key, _)) => {
-                                                    curr_path = ExtrusionPath::new(
-                                                        extrusion_width,
-                                                        self.layer_height,
-                                                        layer.z_height + self.layer_height,
-                                                        extruder_cross_sectional_area_per_mm,
-                                                        Vector2::new(
-                                                            f32::from_bits(key[0]),
-                                                            f32::from_bits(key[1]),
-                                                        ),
-                                                    );
-                                                }
-                                                // map is empty and loop will break
-                                                None => (),
-                                            }
-                                        }
-                                    }
+                                for path in &infill_paths {
+                                    current_layer_paths
+                                        .extend(self.split_bridge_segments(path, bridge_diameter));
                                 }
                             }
-                            None => (),
                         }
                     }
                 }
             }
+            layered_paths.push(current_layer_paths);
         }
-        // TODO: Make this return instead of write.
-        let mut output_gcode = fs::File::create("output.gcode").unwrap();
-        for extrusion_path in extrusion_paths {
-            extrusion_path.write_gcode(&mut output_gcode);
-        }
+        let cooling = CoolingSettings {
+            perimeter_speed: self.perimeter_speed,
+            infill_speed: self.infill_speed,
+            first_layer_speed: self.first_layer_speed,
+            min_layer_time: self.min_layer_time,
+            min_print_speed: self.min_print_speed,
+            fan_speed: self.fan_speed,
+            bridge_speed: self.bridge_speed,
+        };
+        let mut motion_gcode = Vec::<u8>::new();
+        emit_gcode(
+            &layered_paths,
+            &mut motion_gcode,
+            self.retraction_length,
+            self.retraction_speed,
+            self.z_hop,
+            self.travel_speed,
+            self.avoid_crossing_perimeters,
+            &cooling,
+        )
+        .unwrap();
+
+        let variables = self.template_variables(layered_paths.len());
+        let mut gcode = render_gcode_template(&self.start_gcode_template, &variables);
+        gcode.push_str(
+            &String::from_utf8(motion_gcode).expect("G-code writer produced invalid UTF-8"),
+        );
+        gcode.push_str(&render_gcode_template(&self.end_gcode_template, &variables));
+        gcode
     }
 }
 
@@ -412,6 +1545,116 @@ struct Cli {
     #[arg(short, long, default_value = "1")]
     perimeters: u64,
 
+    /// Infill density, from 0 (no infill) to 1 (approximately solid).
+    #[arg(long, default_value = "0.2")]
+    infill_density: f64,
+
+    /// Infill pattern.
+    #[arg(long, value_enum, default_value = "gyroid")]
+    infill_type: InfillType,
+
+    /// Base rectilinear infill angle, in degrees; alternates +90 every other layer.
+    #[arg(long, default_value = "45")]
+    infill_angle: f64,
+
+    /// Length of filament retracted before a non-extruding travel move, in mm.
+    #[arg(long, default_value = "1.0")]
+    retraction_length: f64,
+
+    /// Feedrate for retraction/un-retraction moves, in mm/min.
+    #[arg(long, default_value = "2400")]
+    retraction_speed: f64,
+
+    /// Z lift applied during travel moves, in mm.
+    #[arg(long, default_value = "0.0")]
+    z_hop: f64,
+
+    /// Feedrate for non-extruding travel moves, in mm/min.
+    #[arg(long, default_value = "9000")]
+    travel_speed: f64,
+
+    /// Route travel moves around perimeters instead of crossing them.
+    #[arg(long, default_value_t = false)]
+    avoid_crossing_perimeters: bool,
+
+    /// Steepest angle (from vertical) a surface can have before it's considered an
+    /// overhang needing support, in degrees.
+    #[arg(long, default_value = "45")]
+    support_threshold_angle: f64,
+
+    /// Support infill density, from 0 (no support) to 1 (approximately solid). Also
+    /// acts as the switch for whether support is generated at all.
+    #[arg(long, default_value = "0.0")]
+    support_density: f64,
+
+    /// Air gap between support and the object, in mm: the object's cross-section at
+    /// each layer is inflated outward by this much before being cut out of that
+    /// layer's support region. No vertical gap is left below the overhang itself.
+    #[arg(long, default_value = "0.2")]
+    support_z_gap: f64,
+
+    /// Feedrate for perimeter moves, in mm/min.
+    #[arg(long, default_value = "1800")]
+    perimeter_speed: f64,
+
+    /// Feedrate for infill moves, in mm/min.
+    #[arg(long, default_value = "3000")]
+    infill_speed: f64,
+
+    /// Feedrate for every non-bridge extruding move on the first layer, in mm/min.
+    #[arg(long, default_value = "1200")]
+    first_layer_speed: f64,
+
+    /// Minimum time a layer should take to print, in seconds; feedrates on a faster
+    /// layer are scaled down so it has time to cool before the next layer lands.
+    #[arg(long, default_value = "5")]
+    min_layer_time: f64,
+
+    /// Feedrates are never scaled down past this, in mm/min, even if the layer still
+    /// prints faster than `--min-layer-time`.
+    #[arg(long, default_value = "600")]
+    min_print_speed: f64,
+
+    /// Part cooling fan speed, 0-255. Off on the first layer, except while printing a
+    /// bridge (bridges always run the fan at 255).
+    #[arg(long, default_value = "255")]
+    fan_speed: f64,
+
+    /// Bridge flow as a multiple of the nozzle diameter; bridges extrude a round bead
+    /// this diameter instead of the usual squished profile, since there's no layer
+    /// below for the bead to squash against.
+    #[arg(long, default_value = "1.0")]
+    bridge_flow_ratio: f64,
+
+    /// Feedrate for bridge moves, in mm/min.
+    #[arg(long, default_value = "1800")]
+    bridge_speed: f64,
+
+    /// Where to place each perimeter loop's seam.
+    #[arg(long, value_enum, default_value = "nearest")]
+    seam_position: SeamPosition,
+
+    /// Nozzle temperature, in degrees C.
+    #[arg(long, default_value = "200")]
+    nozzle_temperature: f64,
+
+    /// Bed temperature, in degrees C.
+    #[arg(long, default_value = "60")]
+    bed_temperature: f64,
+
+    /// Start G-code template file; supports `{placeholder}` substitution against the
+    /// slicer configuration, e.g. `{nozzle_temperature}` or `{layer_height * 2}`.
+    #[arg(long, value_parser = clap::value_parser!(PathBuf))]
+    start_gcode: Option<PathBuf>,
+
+    /// End G-code template file; same `{placeholder}` substitution as `--start-gcode`.
+    #[arg(long, value_parser = clap::value_parser!(PathBuf))]
+    end_gcode: Option<PathBuf>,
+
+    /// Path to write the assembled G-code to.
+    #[arg(long, default_value = "output.gcode", value_parser = clap::value_parser!(PathBuf))]
+    output: PathBuf,
+
     /// X axis minimum.
     #[arg(short, long, default_value = "-5")]
     x_min: f64,
@@ -448,6 +1691,17 @@ fn main() {
 
     let tree_def = fidget::rhai::eval(&rhai_def).expect("Object definition invalid.");
 
+    let start_gcode_template = args
+        .start_gcode
+        .as_ref()
+        .map(|path| fs::read_to_string(path).expect("Unable to read start G-code template."))
+        .unwrap_or_default();
+    let end_gcode_template = args
+        .end_gcode
+        .as_ref()
+        .map(|path| fs::read_to_string(path).expect("Unable to read end G-code template."))
+        .unwrap_or_default();
+
     let mut slicer = Slicer::new(
         tree_def,
         args.nozzle_diameter,
@@ -455,6 +1709,30 @@ fn main() {
         args.filament_diameter,
         args.extrusion_width_scalar,
         args.perimeters,
+        args.infill_density,
+        args.infill_type,
+        args.infill_angle,
+        args.retraction_length,
+        args.retraction_speed,
+        args.z_hop,
+        args.travel_speed,
+        args.avoid_crossing_perimeters,
+        args.support_threshold_angle,
+        args.support_density,
+        args.support_z_gap,
+        args.perimeter_speed,
+        args.infill_speed,
+        args.first_layer_speed,
+        args.min_layer_time,
+        args.min_print_speed,
+        args.fan_speed,
+        args.bridge_flow_ratio,
+        args.bridge_speed,
+        args.seam_position,
+        args.nozzle_temperature,
+        args.bed_temperature,
+        start_gcode_template,
+        end_gcode_template,
         args.x_min,
         args.x_max,
         args.y_min,
@@ -462,5 +1740,329 @@ fn main() {
         args.z_min,
         args.z_max,
     );
-    slicer.slice();
+    let gcode = slicer.slice();
+    fs::write(&args.output, gcode).expect("Unable to write output G-code.");
+}
+
+// Shared `Slicer` fixture for tests below: every field is set to a fixed stand-in
+// value except the handful each test actually varies, which are parameters. Kept in
+// one place so a new `Slicer::new` parameter only needs adding here instead of
+// drifting out of sync across every test module's own copy of this call.
+#[cfg(test)]
+mod test_support {
+    use super::*;
+
+    pub(super) fn test_slicer(
+        perimeters: u64,
+        seam_position: SeamPosition,
+        x_min: f64,
+        y_min: f64,
+    ) -> Slicer {
+        Slicer::new(
+            Tree::x(),
+            0.4,
+            0.2,
+            1.75,
+            1.05,
+            perimeters,
+            0.2,
+            InfillType::Gyroid,
+            45.,
+            1.,
+            2400.,
+            0.,
+            9000.,
+            false,
+            45.,
+            0.,
+            0.2,
+            1800.,
+            3000.,
+            1200.,
+            5.,
+            600.,
+            255.,
+            1.,
+            1800.,
+            seam_position,
+            200.,
+            60.,
+            String::new(),
+            String::new(),
+            x_min,
+            5.,
+            y_min,
+            5.,
+            0.,
+            5.,
+        )
+    }
+}
+
+#[cfg(test)]
+mod seam_tests {
+    use super::*;
+
+    // `seam_index_for` only reads `seam_position` and the reference point's origin.
+    fn test_slicer(seam_position: SeamPosition, x_min: f64, y_min: f64) -> Slicer {
+        test_support::test_slicer(1, seam_position, x_min, y_min)
+    }
+
+    fn closed_loop(points: &[(f32, f32)]) -> ExtrusionPath {
+        let mut path = ExtrusionPath::new(
+            0.4,
+            0.2,
+            0.2,
+            2.4,
+            Vector2::new(points[0].0, points[0].1),
+            true,
+            LayerType::Standard,
+            None,
+        );
+        for &(x, y) in &points[1..] {
+            path.add_to_path(Vector2::new(x, y), 0.4);
+        }
+        path.add_to_path(Vector2::new(points[0].0, points[0].1), 0.4);
+        path
+    }
+
+    #[test]
+    fn exterior_turn_angle_is_positive_for_convex_and_negative_for_concave() {
+        // A square corner, traversed counter-clockwise, is convex.
+        let convex = exterior_turn_angle(
+            Vector2::new(0., 0.),
+            Vector2::new(4., 0.),
+            Vector2::new(4., 4.),
+        );
+        assert!(convex > 0.);
+
+        // The reflex vertex of a dart (arrow) shape is concave.
+        let concave = exterior_turn_angle(
+            Vector2::new(4., 4.),
+            Vector2::new(2., 2.),
+            Vector2::new(0., 4.),
+        );
+        assert!(concave < 0.);
+    }
+
+    #[test]
+    fn aligned_seam_prefers_the_concave_vertex() {
+        // A dart: (2, 2) is the only reflex (concave) vertex, and it isn't the one
+        // nearest the reference point (vertex 0 is) - if the sign were flipped
+        // (preferring convex corners, as it briefly did), this would instead pick the
+        // sharp convex tip at vertex 4.
+        let dart = closed_loop(&[(0., 0.), (4., 0.), (4., 4.), (2., 2.), (0., 4.)]);
+        let slicer = test_slicer(SeamPosition::Aligned, 0., 0.);
+        assert_eq!(slicer.seam_index_for(&dart, None), 3);
+    }
+
+    #[test]
+    fn rear_seam_picks_the_highest_vertex() {
+        let square = closed_loop(&[(0., 0.), (4., 0.), (4., 4.), (0., 4.)]);
+        let slicer = test_slicer(SeamPosition::Rear, 0., 0.);
+        let seam_index = slicer.seam_index_for(&square, None);
+        assert_eq!(square.paths[seam_index].y, 4.);
+    }
+}
+
+#[cfg(test)]
+mod variable_width_perimeter_tests {
+    use super::*;
+
+    #[test]
+    fn only_the_last_ring_index_is_innermost() {
+        // With 2 perimeters, rings are walked as indices 1 (innermost) then 0
+        // (outermost) - index 0 being "innermost" would apply variable width to the
+        // outer wall instead.
+        assert!(!is_innermost_perimeter(0, 2));
+        assert!(is_innermost_perimeter(1, 2));
+    }
+
+    #[test]
+    fn single_perimeter_is_its_own_innermost_ring() {
+        assert!(is_innermost_perimeter(0, 1));
+    }
+
+    #[test]
+    fn bead_width_merges_to_minimum_below_one_nominal_width() {
+        // `test_support::test_slicer`'s object is `Tree::x()`, whose SDF at any point
+        // is just its x coordinate, so thickness = 2 * |x|. At x = 0.1 that's 0.2,
+        // well under the nominal 0.4 width - too little for even one full bead.
+        let slicer = test_support::test_slicer(1, SeamPosition::Nearest, 0., 0.);
+        let width = slicer.local_bead_width(Vector2::new(0.1, 0.), 0., 0.4);
+        assert_eq!(width, 0.2);
+    }
+
+    #[test]
+    fn bead_width_widens_to_fill_a_gap_between_one_and_two_nominal_widths() {
+        // thickness = 2 * |x| = 0.5 at x = 0.25, between one (0.4) and two (0.8)
+        // nominal widths - the bead should widen to cover it exactly. (0.25 is exact
+        // in f32, so the f32 -> f64 conversion adds no rounding error.)
+        let slicer = test_support::test_slicer(1, SeamPosition::Nearest, 0., 0.);
+        let width = slicer.local_bead_width(Vector2::new(0.25, 0.), 0., 0.4);
+        assert_eq!(width, 0.5);
+    }
+
+    #[test]
+    fn bead_width_caps_at_twice_nominal_above_two_nominal_widths() {
+        // thickness = 2 * |x| = 2.0 at x = 1.0, well past two nominal widths (0.8) -
+        // the bead is capped rather than growing further, leaving the slack for
+        // another fixed-width ring.
+        let slicer = test_support::test_slicer(1, SeamPosition::Nearest, 0., 0.);
+        let width = slicer.local_bead_width(Vector2::new(1.0, 0.), 0., 0.4);
+        assert_eq!(width, 0.8);
+    }
+}
+
+#[cfg(test)]
+mod template_tests {
+    use super::*;
+
+    #[test]
+    fn renders_a_bare_variable() {
+        let mut variables = HashMap::new();
+        variables.insert("foo".to_string(), 3.5);
+        assert_eq!(render_gcode_template("X{foo}", &variables), "X3.5");
+    }
+
+    #[test]
+    fn renders_a_left_to_right_operator_chain() {
+        // No precedence: `2 + 3 * 2` is `(2 + 3) * 2`, not `2 + (3 * 2)`.
+        let variables = HashMap::new();
+        assert_eq!(render_gcode_template("{2 + 3 * 2}", &variables), "10");
+    }
+
+    #[test]
+    fn whole_number_results_have_no_trailing_decimal() {
+        let variables = HashMap::new();
+        assert_eq!(render_gcode_template("{1 / 2 * 2}", &variables), "1");
+        assert_eq!(render_gcode_template("{1 / 4}", &variables), "0.25");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown template variable")]
+    fn unknown_variable_panics() {
+        eval_template_token("not_a_real_variable", &HashMap::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "unterminated template placeholder")]
+    fn unterminated_placeholder_panics() {
+        render_gcode_template("M104 S{nozzle_temperature", &HashMap::new());
+    }
+}
+
+#[cfg(test)]
+mod travel_routing_tests {
+    use super::*;
+
+    #[test]
+    fn properly_crossing_segments_are_detected() {
+        // The two diagonals of a 4x4 square cross transversally in the middle.
+        assert!(segments_properly_cross(
+            Vector2::new(0., 0.),
+            Vector2::new(4., 4.),
+            Vector2::new(0., 4.),
+            Vector2::new(4., 0.),
+        ));
+    }
+
+    #[test]
+    fn segments_only_touching_at_an_endpoint_do_not_cross() {
+        // Two sides of a square sharing a corner - allowed, so travel can hug a
+        // perimeter's inside corner without being treated as crossing it.
+        assert!(!segments_properly_cross(
+            Vector2::new(0., 0.),
+            Vector2::new(4., 0.),
+            Vector2::new(4., 0.),
+            Vector2::new(4., 4.),
+        ));
+    }
+
+    #[test]
+    fn travel_routes_around_a_wall_between_start_and_end() {
+        // A thin wall from x=2 to x=3 spanning well past the travel's y, blocking the
+        // direct path from (0, 2) to (5, 2).
+        let wall = vec![vec![
+            Vector2::new(2., -1.),
+            Vector2::new(2., 5.),
+            Vector2::new(3., 5.),
+            Vector2::new(3., -1.),
+            Vector2::new(2., -1.),
+        ]];
+        let start = Vector2::new(0., 2.);
+        let end = Vector2::new(5., 2.);
+        let route = route_travel(start, end, &wall);
+
+        assert_eq!(*route.first().unwrap(), start);
+        assert_eq!(*route.last().unwrap(), end);
+        // It had to detour around a corner rather than going straight through the wall.
+        assert!(route.len() > 2);
+        for pair in route.windows(2) {
+            assert!(!segment_crosses_any_perimeter(pair[0], pair[1], &wall));
+        }
+    }
+
+    #[test]
+    fn travel_goes_direct_when_nothing_is_in_the_way() {
+        let wall = vec![vec![
+            Vector2::new(2., -1.),
+            Vector2::new(2., 5.),
+            Vector2::new(3., 5.),
+            Vector2::new(3., -1.),
+            Vector2::new(2., -1.),
+        ]];
+        // Both points are on the same side of the wall - no detour needed.
+        let route = route_travel(Vector2::new(0., 0.), Vector2::new(1., 1.), &wall);
+        assert_eq!(route, vec![Vector2::new(0., 0.), Vector2::new(1., 1.)]);
+    }
+}
+
+#[cfg(test)]
+mod infill_tests {
+    use super::*;
+
+    fn test_slicer() -> Slicer {
+        test_support::test_slicer(1, SeamPosition::Nearest, 0., 0.)
+    }
+
+    fn square_boundary() -> ExtrusionPath {
+        let mut path = ExtrusionPath::new(
+            0.4,
+            0.2,
+            0.2,
+            2.4,
+            Vector2::new(0., 0.),
+            false,
+            LayerType::Standard,
+            None,
+        );
+        for &(x, y) in &[(4., 0.), (4., 4.), (0., 4.), (0., 0.)] {
+            path.add_to_path(Vector2::new(x, y), 0.4);
+        }
+        path
+    }
+
+    #[test]
+    fn rasterizes_a_square_at_a_known_angle_and_spacing() {
+        let slicer = test_slicer();
+        let boundary = square_boundary();
+        let lines =
+            slicer.rectilinear_infill_paths(&[boundary], 0., 2., 0.2, 0.4, 2.4, LayerType::Standard);
+
+        let endpoints: Vec<(Vector2<f32>, Vector2<f32>)> = lines
+            .iter()
+            .map(|path| (path.paths[0], path.paths[1]))
+            .collect();
+        // Scanlines at x=0, x=2 and x=4; x=4 lands exactly on the far edge and
+        // produces no crossings, so only two fill lines come out. Consecutive
+        // scanlines start from opposite ends, cutting travel between them.
+        assert_eq!(
+            endpoints,
+            vec![
+                (Vector2::new(0., 0.), Vector2::new(0., 4.)),
+                (Vector2::new(2., 4.), Vector2::new(2., 0.)),
+            ]
+        );
+    }
 }